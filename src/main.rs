@@ -33,6 +33,28 @@ impl WaterState {
     }
 }
 
+/// Which signal to extract from a `WaterState` record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    /// The NOAA-verified observed water level.
+    Verified,
+    /// The NOAA-predicted (purely astronomical) water level.
+    Predicted,
+    /// `Verified - Predicted`, i.e. the non-tidal residual (storm surge, model error, ...).
+    Residual,
+}
+
+impl Signal {
+    /// Extracts this signal's value from a single record.
+    fn value(&self, record: &WaterState) -> f64 {
+        match self {
+            Signal::Verified => record.verified,
+            Signal::Predicted => record.predicted,
+            Signal::Residual => record.verified - record.predicted,
+        }
+    }
+}
+
 /// A single data point about the water level
 /// `time` kept in seconds since the first data point
 struct DataPoint {
@@ -41,18 +63,20 @@ struct DataPoint {
 }
 
 /// A single point of the Fourier transform of the data
-/// Frequency stored in Hz
+/// Frequency stored in Hz, phase stored in radians
 struct FtPoint {
     freq: f64,
     amplitude: f64,
+    phase: f64,
 }
 
 /// A dataset representing the variability of the water level over some period of time
 struct DataSet(Vec<DataPoint>);
 
 impl DataSet {
-    /// Reads the dataset from a CSV file
-    fn get_data<P: AsRef<Path>>(path: P) -> Self {
+    /// Reads the dataset from a CSV file, extracting the given `signal` as each point's water
+    /// level.
+    fn get_data<P: AsRef<Path>>(path: P, signal: Signal) -> Self {
         let mut rdr = csv::Reader::from_path(path).unwrap();
         let mut result = vec![];
         let mut records_iter = rdr.deserialize();
@@ -60,14 +84,14 @@ impl DataSet {
         let first_datetime = first_record.datetime();
         result.push(DataPoint {
             time: 0.0,
-            water_level: first_record.verified,
+            water_level: signal.value(&first_record),
         });
 
         for record in records_iter {
             let record = record.unwrap();
             let data_point = DataPoint {
                 time: (record.datetime() - first_datetime).num_seconds() as f64,
-                water_level: record.verified,
+                water_level: signal.value(&record),
             };
             result.push(data_point);
         }
@@ -80,6 +104,48 @@ impl DataSet {
         self.0.last().unwrap().time - self.0.first().unwrap().time
     }
 
+    /// Applies a Tukey (tapered-cosine) window to the water levels, in place, to suppress the
+    /// spectral leakage caused by transforming a finite record.
+    ///
+    /// `p` is the fraction of the record, in `0.0..=1.0`, covered by the taper, split evenly
+    /// between the start and the end of the record: `p = 0.0` leaves the data untouched, while
+    /// `p = 1.0` applies a full Hann window. Within each ramp the weight follows the standard
+    /// raised-cosine shape `0.5 * (1.0 + cos(pi * (r - 1.0)))`, where `r` is the fraction of the
+    /// way through that ramp.
+    fn apply_tukey_window(&mut self, p: f64) {
+        if p <= 0.0 {
+            return;
+        }
+
+        let t0 = self.0.first().unwrap().time;
+        let length = self.period_length();
+        let half_ramp = p / 2.0;
+
+        for point in &mut self.0 {
+            let x = (point.time - t0) / length; // 0..=1 position within the record
+            let r = if x < half_ramp {
+                x / half_ramp
+            } else if x > 1.0 - half_ramp {
+                (1.0 - x) / half_ramp
+            } else {
+                1.0
+            };
+            let weight = 0.5 * (1.0 + (std::f64::consts::PI * (r - 1.0)).cos());
+            point.water_level *= weight;
+        }
+    }
+
+    /// The mean of the Tukey window's weights for a given taper fraction `p`, used to renormalize
+    /// amplitudes so that peak heights stay comparable across different taper settings.
+    fn tukey_window_mean(p: f64) -> f64 {
+        if p <= 0.0 {
+            return 1.0;
+        }
+        // The two ramps each contribute a mean weight of 0.5 over their span, and the flat
+        // central region contributes 1.0 over the remaining span.
+        1.0 - p / 2.0
+    }
+
     /// Calculates the integral over the covered period of the water level variability function
     /// multiplied by a harmonic function with frequency `freq`
     /// (basically: calculates a single point of the Fourier transform of the dataset)
@@ -125,6 +191,168 @@ impl DataSet {
     }
 }
 
+/// A named tidal harmonic constituent, identified by its Doodson/NOAA speed in degrees per
+/// solar hour.
+struct Constituent {
+    name: &'static str,
+    speed_deg_per_hour: f64,
+}
+
+impl Constituent {
+    /// The constituent's angular speed in radians per second, matching the units `DataPoint::time`
+    /// is measured in.
+    fn angular_speed(&self) -> f64 {
+        self.speed_deg_per_hour.to_radians() / 3600.0
+    }
+}
+
+/// The eight standard tidal constituents, as tabulated by NOAA.
+const STANDARD_CONSTITUENTS: &[Constituent] = &[
+    Constituent {
+        name: "M2",
+        speed_deg_per_hour: 28.9841042,
+    },
+    Constituent {
+        name: "S2",
+        speed_deg_per_hour: 30.0,
+    },
+    Constituent {
+        name: "N2",
+        speed_deg_per_hour: 28.4397295,
+    },
+    Constituent {
+        name: "K2",
+        speed_deg_per_hour: 30.0821373,
+    },
+    Constituent {
+        name: "K1",
+        speed_deg_per_hour: 15.0410686,
+    },
+    Constituent {
+        name: "O1",
+        speed_deg_per_hour: 13.9430356,
+    },
+    Constituent {
+        name: "P1",
+        speed_deg_per_hour: 14.9589314,
+    },
+    Constituent {
+        name: "Q1",
+        speed_deg_per_hour: 13.3986609,
+    },
+];
+
+/// The fitted amplitude and phase of a single tidal constituent (or, for the `"Z0"` entry, the
+/// mean water level).
+struct ConstituentResult {
+    name: &'static str,
+    amplitude: f64,
+    phase: f64,
+}
+
+/// Solves the square linear system `a * x = b` by Gaussian elimination with partial pivoting.
+fn solve_linear_system(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Vec<f64> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&r1, &r2| a[r1][col].abs().partial_cmp(&a[r2][col].abs()).unwrap())
+            .unwrap();
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let diag = a[col][col];
+        let pivot_row = a[col].clone();
+        for row in (col + 1)..n {
+            let factor = a[row][col] / diag;
+            for (elem, pivot_elem) in a[row].iter_mut().zip(pivot_row.iter()).skip(col) {
+                *elem -= factor * pivot_elem;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    x
+}
+
+/// Fits the dataset to a sum of sinusoids at the given constituents' frequencies plus a constant
+/// mean term, by least squares.
+///
+/// The model is `mean + sum_i(a_i * cos(w_i * t) + b_i * sin(w_i * t))`, fitted by solving the
+/// normal equations `A^T A x = A^T y` for a design matrix `A` with columns
+/// `[1, cos(w_i t), sin(w_i t), ...]`. Unlike `fourier`, this does not require an evenly spaced
+/// frequency grid since it only evaluates the physically expected tidal frequencies.
+fn harmonic_fit(data: &DataSet, constituents: &[Constituent]) -> Vec<ConstituentResult> {
+    let n_params = 1 + 2 * constituents.len();
+    let omegas: Vec<f64> = constituents.iter().map(Constituent::angular_speed).collect();
+
+    let mut ata = vec![vec![0.0; n_params]; n_params];
+    let mut aty = vec![0.0; n_params];
+    for point in &data.0 {
+        let mut row = Vec::with_capacity(n_params);
+        row.push(1.0);
+        for &omega in &omegas {
+            row.push((omega * point.time).cos());
+            row.push((omega * point.time).sin());
+        }
+
+        for i in 0..n_params {
+            aty[i] += row[i] * point.water_level;
+            for j in 0..n_params {
+                ata[i][j] += row[i] * row[j];
+            }
+        }
+    }
+
+    let x = solve_linear_system(ata, aty);
+
+    let mean = ConstituentResult {
+        name: "Z0",
+        amplitude: x[0],
+        phase: 0.0,
+    };
+    let fitted = constituents.iter().enumerate().map(|(i, constituent)| {
+        let a = x[1 + 2 * i];
+        let b = x[2 + 2 * i];
+        ConstituentResult {
+            name: constituent.name,
+            amplitude: (a * a + b * b).sqrt(),
+            phase: b.atan2(a),
+        }
+    });
+
+    std::iter::once(mean).chain(fitted).collect()
+}
+
+/// Synthesizes the water level at each of the given `times` from a set of fitted constituents
+/// (as returned by `harmonic_fit`), by summing `mean + sum_i(A_i * cos(w_i * t - phi_i))`.
+fn reconstruct(constituents: &[ConstituentResult], times: &[f64]) -> Vec<f64> {
+    times
+        .iter()
+        .map(|&t| {
+            constituents
+                .iter()
+                .map(|result| {
+                    if result.name == "Z0" {
+                        result.amplitude
+                    } else {
+                        let omega = STANDARD_CONSTITUENTS
+                            .iter()
+                            .find(|constituent| constituent.name == result.name)
+                            .expect("reconstruct only supports the standard constituents")
+                            .angular_speed();
+                        result.amplitude * (omega * t - result.phase).cos()
+                    }
+                })
+                .sum()
+        })
+        .collect()
+}
+
 /// Calculates the Fourier transform of the given dataset.
 /// The result will cover the range of frequencies starting at `start_freq`, ending at `end_freq`
 /// and have a data point every `step`.
@@ -133,12 +361,13 @@ fn fourier(data: &DataSet, start_freq: f64, end_freq: f64, step: f64) -> Vec<FtP
 
     let mut current_freq = start_freq;
     while current_freq <= end_freq {
-        // We'll only be interested in the amplitude, which is the norm of the complex value of the
-        // Fourier transform.
-        let amplitude = data.integrate_freq(current_freq).norm();
+        // The amplitude is the norm of the complex value of the Fourier transform, and the phase
+        // is its argument - both come for free from the same `integrate_freq` call.
+        let point = data.integrate_freq(current_freq);
         result.push(FtPoint {
             freq: current_freq,
-            amplitude,
+            amplitude: point.norm(),
+            phase: point.arg(),
         });
         current_freq += step;
     }
@@ -146,9 +375,207 @@ fn fourier(data: &DataSet, start_freq: f64, end_freq: f64, step: f64) -> Vec<FtP
     result
 }
 
+/// Runs `fourier` over a sliding window across the dataset.
+/// The window is `window_secs` long and advances by `(1.0 - overlap_fraction) * window_secs`
+/// each step, with its data points' time re-zeroed to the start of the window. Each result block
+/// is tagged with the time at the center of its window (seconds since the first data point in
+/// `data`).
+fn spectrogram(
+    data: &DataSet,
+    window_secs: f64,
+    overlap_fraction: f64,
+    taper: f64,
+    start_freq: f64,
+    end_freq: f64,
+    step: f64,
+) -> Vec<(f64, Vec<FtPoint>)> {
+    assert!(
+        (0.0..1.0).contains(&overlap_fraction),
+        "overlap_fraction must be in 0.0..1.0, got {}",
+        overlap_fraction
+    );
+
+    let record_start = data.0.first().unwrap().time;
+    let record_end = data.0.last().unwrap().time;
+    let advance = (1.0 - overlap_fraction) * window_secs;
+    let window_mean = DataSet::tukey_window_mean(taper);
+
+    let mut result = vec![];
+    let mut window_start = record_start;
+    while window_start + window_secs <= record_end {
+        let window_end = window_start + window_secs;
+        let window_points: Vec<DataPoint> = data
+            .0
+            .iter()
+            .filter(|point| point.time >= window_start && point.time < window_end)
+            .map(|point| DataPoint {
+                time: point.time - window_start,
+                water_level: point.water_level,
+            })
+            .collect();
+
+        // A window with fewer than two points can't be transformed (with no points
+        // `period_length` panics, and with one it is zero, which `integrate_freq` divides by),
+        // so just skip it.
+        if window_points.len() >= 2 {
+            let mut window_data = DataSet(window_points);
+            // The taper is applied per window, not to the whole record, so that each window is
+            // tapered (and renormalized) relative to its own span.
+            window_data.apply_tukey_window(taper);
+            let center_time = window_start + window_secs / 2.0;
+            let points = fourier(&window_data, start_freq, end_freq, step)
+                .into_iter()
+                .map(|point| FtPoint {
+                    amplitude: point.amplitude / window_mean,
+                    ..point
+                })
+                .collect();
+            result.push((center_time, points));
+        }
+
+        window_start += advance;
+    }
+
+    result
+}
+
+/// A local maximum of a Fourier sweep
+struct Peak {
+    freq: f64,
+    amplitude: f64,
+    phase: f64,
+}
+
+/// Finds local maxima in a Fourier sweep whose amplitude is at least `threshold` times the
+/// sweep's noise floor, estimated as the median amplitude across the whole sweep.
+fn detect_peaks(points: &[FtPoint], threshold: f64) -> Vec<Peak> {
+    if points.len() < 3 {
+        return vec![];
+    }
+
+    // `integrate_freq(0.0)` divides by zero, so a sweep starting at 0 Hz has a NaN amplitude;
+    // exclude non-finite amplitudes before estimating the noise floor.
+    let mut amplitudes: Vec<f64> = points
+        .iter()
+        .map(|point| point.amplitude)
+        .filter(|amplitude| amplitude.is_finite())
+        .collect();
+    if amplitudes.is_empty() {
+        return vec![];
+    }
+    amplitudes.sort_by(f64::total_cmp);
+    let noise_floor = amplitudes[amplitudes.len() / 2];
+
+    points
+        .windows(3)
+        .filter_map(|window| {
+            let (prev, cur, next) = (&window[0], &window[1], &window[2]);
+            let is_local_max = cur.amplitude > prev.amplitude && cur.amplitude > next.amplitude;
+            if is_local_max && cur.amplitude >= threshold * noise_floor {
+                Some(Peak {
+                    freq: cur.freq,
+                    amplitude: cur.amplitude,
+                    phase: cur.phase,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Looks up the value following a named flag (e.g. `--taper 0.1`) among the command-line
+/// arguments, if present.
+fn arg_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = env::args().collect();
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
 fn main() {
     // Call the script as:
-    // ./tides-ft path-to-csv-file [start_freq [end_freq [step]]]
+    // ./tides-ft path-to-csv-file [start_freq [end_freq [step]]] [--harmonic] [--phase]
+    //     [--taper p] [--spectrogram --window secs [--overlap frac]]
+    //     [--signal verified|predicted|residual]
+    //     [--reconstruct [--from t0] [--to t1] [--dt step]] [--peaks [--threshold k]]
+    //
+    // --harmonic switches to a least-squares fit against the standard tidal constituents
+    // instead of sweeping a frequency grid.
+    // --phase adds a third output column with the phase in radians.
+    // --taper p applies a Tukey window (taper fraction p, in 0.0..=1.0) to the water levels
+    // before transforming, to reduce spectral leakage.
+    // --spectrogram slides a window of --window seconds (overlapping by --overlap, default 0.5)
+    // across the record and prints a "time freq amplitude" table instead of a single transform.
+    // --signal selects which column to transform: the verified water level (default), the
+    // predicted water level, or the verified-minus-predicted residual.
+    // --reconstruct fits the standard constituents and synthesizes a predicted tide curve from
+    // --from to --to (seconds since the first data point, defaulting to the whole record) every
+    // --dt seconds (default one hour), instead of transforming at all.
+    // --peaks reports only local maxima rising --threshold multiples (default 5.0) above the
+    // sweep's noise floor, instead of the full sweep.
+    let print_phase = env::args().any(|arg| arg == "--phase");
+    let taper: f64 = arg_value("--taper")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(0.0);
+    let signal = match arg_value("--signal").as_deref() {
+        Some("predicted") => Signal::Predicted,
+        Some("residual") => Signal::Residual,
+        Some("verified") | None => Signal::Verified,
+        Some(other) => panic!(
+            "unknown --signal {}, expected verified/predicted/residual",
+            other
+        ),
+    };
+
+    if env::args().any(|arg| arg == "--harmonic") {
+        assert!(
+            taper <= 0.0,
+            "--taper is not supported together with --harmonic: tapering the record before a \
+             fit at fixed frequencies biases the fitted amplitudes and phases, it doesn't just \
+             scale them"
+        );
+        let file_path = env::args_os().nth(1).unwrap();
+        let data = DataSet::get_data(file_path, signal);
+        for result in harmonic_fit(&data, STANDARD_CONSTITUENTS) {
+            println!("{} {} {}", result.name, result.amplitude, result.phase);
+        }
+        return;
+    }
+
+    if env::args().any(|arg| arg == "--reconstruct") {
+        assert!(
+            taper <= 0.0,
+            "--taper is not supported together with --reconstruct: tapering the record before a \
+             fit at fixed frequencies biases the fitted amplitudes and phases, it doesn't just \
+             scale them"
+        );
+        let file_path = env::args_os().nth(1).unwrap();
+        let data = DataSet::get_data(file_path, signal);
+        let constituents = harmonic_fit(&data, STANDARD_CONSTITUENTS);
+
+        let from: f64 = arg_value("--from")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0.0);
+        let to: f64 = arg_value("--to")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| data.period_length());
+        let dt: f64 = arg_value("--dt").and_then(|v| v.parse().ok()).unwrap_or(3600.0);
+
+        let mut times = vec![];
+        let mut t = from;
+        while t <= to {
+            times.push(t);
+            t += dt;
+        }
+
+        for (t, level) in times.iter().zip(reconstruct(&constituents, &times)) {
+            println!("{} {}", t, level);
+        }
+        return;
+    }
+
     let file_path = env::args_os().nth(1).unwrap();
     let start_freq: f64 = env::args_os()
         .nth(2)
@@ -166,12 +593,62 @@ fn main() {
         .and_then(|arg| arg.parse().ok())
         // default step such that there will be 30000 data points between start and end freqs
         .unwrap_or(5.0 / 86400.0 / 30000.0);
-    let data = DataSet::get_data(file_path);
+    let mut data = DataSet::get_data(file_path, signal);
+
+    if env::args().any(|arg| arg == "--spectrogram") {
+        let window_secs: f64 = arg_value("--window")
+            .and_then(|w| w.parse().ok())
+            .expect("--spectrogram requires --window secs");
+        let overlap: f64 = arg_value("--overlap")
+            .and_then(|o| o.parse().ok())
+            .unwrap_or(0.5);
+
+        // The taper, if any, is applied per window inside `spectrogram`, not to `data` here.
+        let blocks = spectrogram(&data, window_secs, overlap, taper, start_freq, end_freq, step);
+        for (center_time, points) in blocks {
+            for point in points {
+                println!("{} {} {}", center_time, point.freq * 86400.0, point.amplitude);
+            }
+        }
+        return;
+    }
 
-    let fourier = fourier(&data, start_freq, end_freq, step);
+    data.apply_tukey_window(taper);
+    let window_mean = DataSet::tukey_window_mean(taper);
+
+    let fourier: Vec<FtPoint> = fourier(&data, start_freq, end_freq, step)
+        .into_iter()
+        .map(|point| FtPoint {
+            amplitude: point.amplitude / window_mean,
+            ..point
+        })
+        .collect();
+
+    if env::args().any(|arg| arg == "--peaks") {
+        let threshold: f64 = arg_value("--threshold")
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(5.0);
+        for peak in detect_peaks(&fourier, threshold) {
+            if print_phase {
+                println!(
+                    "{} {} {}",
+                    peak.freq * 86400.0,
+                    peak.amplitude,
+                    peak.phase
+                );
+            } else {
+                println!("{} {}", peak.freq * 86400.0, peak.amplitude);
+            }
+        }
+        return;
+    }
 
     // print the results to stdout
     for point in fourier.into_iter() {
-        println!("{} {}", point.freq * 86400.0, point.amplitude);
+        if print_phase {
+            println!("{} {} {}", point.freq * 86400.0, point.amplitude, point.phase);
+        } else {
+            println!("{} {}", point.freq * 86400.0, point.amplitude);
+        }
     }
 }